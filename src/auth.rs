@@ -1,17 +1,26 @@
+pub mod backend;
+pub mod session;
 pub mod user;
 
+use crate::auth::backend::AuthBackend;
+use crate::auth::session::SessionStore;
 use crate::auth::user::User;
 use anyhow::{anyhow, Result};
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use bincode::{Decode, Encode};
 use dashmap::DashMap;
-use std::fs;
-use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::path::Path;
+use std::net::IpAddr;
+use std::time::Duration;
 
 pub type SessionToken = [u8; 16];
 
+/// How long an issued session token may go unused before a reconnecting
+/// client is required to fully re-authenticate.
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 10);
+
+/// The hard ceiling on how long after issuance a session token can ever be
+/// used to resume a session, regardless of how recently it was used.
+const SESSION_MAX_RECONNECT_WINDOW: Duration = Duration::from_secs(60 * 60 * 24);
+
 /// Represents the internal authentication state for a session.
 #[derive(Clone, Debug, PartialEq)]
 pub enum AuthState {
@@ -23,7 +32,7 @@ pub enum AuthState {
 #[derive(Encode, Decode, Debug)]
 pub enum AuthClientMessage {
     Authentication(String, String),
-    SessionToken(SessionToken),
+    SessionToken(String, SessionToken),
 }
 
 /// Represents an authentication message sent by the server.
@@ -34,25 +43,32 @@ pub enum AuthServerMessage {
     Failed,
 }
 
-/// Represents a module providing basic authentication functionality.
+/// Represents a module providing authentication functionality backed by a
+/// pluggable `AuthBackend` (e.g. a flat users file or PAM).
+///
+/// On top of whatever session validity the backend itself tracks, `Auth`
+/// layers a `SessionStore` so that a client which loses its QUIC connection
+/// can reconnect and resume its session with `AuthClientMessage::SessionToken`
+/// instead of re-running the full authentication flow, within a bounded
+/// idle timeout and reconnect window.
 pub struct Auth {
-    users: DashMap<String, User>,
-    hasher: Argon2<'static>,
+    backend: Box<dyn AuthBackend>,
+    sessions: SessionStore,
 }
 
 impl Auth {
-    /// Creates a new instance of the authentication module.
+    /// Creates a new instance of the authentication module using the given backend.
     ///
     /// ### Arguments
-    /// - `users` - a map of users (username -> `User`)
-    pub fn new(users: DashMap<String, User>) -> Self {
+    /// - `backend` - the `AuthBackend` implementation to delegate authentication to
+    pub fn new(backend: Box<dyn AuthBackend>) -> Self {
         Self {
-            users,
-            hasher: Argon2::default(),
+            backend,
+            sessions: SessionStore::new(SESSION_IDLE_TIMEOUT, SESSION_MAX_RECONNECT_WINDOW),
         }
     }
 
-    /// Authenticates the given users and returns a session token if successful.
+    /// Authenticates the given credentials and returns a session token if successful.
     ///
     /// ### Arguments
     /// - `username` - the username
@@ -61,93 +77,83 @@ impl Auth {
     /// ### Returns
     /// - `Bytes` containing the session token
     pub async fn authenticate(&self, username: &str, password: String) -> Result<SessionToken> {
-        let user = self
-            .users
-            .get(username)
-            .ok_or_else(|| anyhow!("Unknown user: {username}"))?;
-        let password_hash = PasswordHash::new(user.password_hash()).map_err(|err| {
-            anyhow!("Could not parse user password hash for user '{username}': {err}")
-        })?;
-
-        self.hasher
-            .verify_password(password.as_bytes(), &password_hash)
-            .map_err(|err| anyhow!("Could not verify credentials for user '{username}': {err}"))?;
-
-        Ok(user.new_session().await)
+        let session_token = self.backend.authenticate(username, password).await?;
+        self.sessions.track(username, session_token);
+
+        Ok(session_token)
     }
 
-    /// Verifies the given session token for the specified user.
+    /// Verifies the given session token for the specified user, either as
+    /// part of an initial handshake or to resume a session after a
+    /// reconnect. A successful resume refreshes the token's idle timer.
     ///
     /// ### Arguments
     /// - `username` - the username
     /// - `session_token` - the session token
     ///
     /// ### Returns
-    /// - `true` if the session token is valid, `false` otherwise
+    /// - `true` if the session token is valid and has not expired, `false` otherwise
     pub fn verify_session_token(
         &self,
         username: &str,
         session_token: SessionToken,
     ) -> Result<bool> {
-        let user = self
-            .users
-            .get(username)
-            .ok_or_else(|| anyhow!("Unknown user: {username}"))?;
-
-        Ok(user.check_session_validity(session_token))
-    }
-
-    /// Resets all user sessions.
-    pub fn reset(&self) {
-        for entry in self.users.iter() {
-            entry.value().reset();
+        if !self.backend.verify_session_token(username, session_token)? {
+            return Ok(false);
         }
+
+        Ok(self.sessions.try_resume(username, session_token))
     }
 
-    /// Loads the contents of a file with users and their passwords hashes into a map.
+    /// Records that `session_token` was assigned `addr` as its tunnel
+    /// address, so a later reconnect presenting the same token can be
+    /// re-bound to it via `resume_session_addr` instead of drawing a fresh
+    /// address from the pool.
     ///
     /// ### Arguments
-    /// - `users_file` - path to the users file
-    ///
-    /// ### Returns
-    /// - `DashMap` containing all loaded users
-    pub fn load_users_file(users_file: &Path) -> Result<DashMap<String, User>> {
-        let file = File::open(users_file)?;
-        let lines = BufReader::new(file).lines();
-
-        let result: DashMap<String, User> = DashMap::new();
-
-        for line in lines {
-            let user: User = line?.try_into()?;
-            result.insert(user.username().clone(), user);
-        }
-
-        Ok(result)
+    /// - `session_token` - the session token returned by `authenticate`
+    /// - `addr` - the tunnel address assigned to that session
+    pub fn bind_session_addr(&self, session_token: SessionToken, addr: IpAddr) {
+        self.sessions.set_assigned_addr(session_token, addr);
     }
 
-    /// Writes the users and their password hashes into the specified file
+    /// Resumes a session after a client reconnects and presents
+    /// `AuthClientMessage::SessionToken`, returning the tunnel address it was
+    /// previously assigned so the caller can re-bind the client to it via
+    /// `TunWorker::add_connection` instead of allocating a new one.
     ///
     /// ### Arguments
-    /// - `users_file` - path to the users file
-    /// - `users` - a map of users (username -> `User`)
-    pub fn save_users_file(users_file: &Path, users: DashMap<String, User>) -> Result<()> {
-        if users_file.exists() {
-            fs::remove_file(users_file)?;
+    /// - `username` - the username
+    /// - `session_token` - the session token presented by the reconnecting client
+    pub fn resume_session_addr(&self, username: &str, session_token: SessionToken) -> Result<IpAddr> {
+        if !self.backend.verify_session_token(username, session_token)? {
+            return Err(anyhow!("Session token is no longer valid for '{username}'"));
         }
 
-        let file = File::create(users_file)?;
-        let mut writer = BufWriter::new(file);
+        self.sessions
+            .try_resume_addr(username, session_token)
+            .ok_or_else(|| anyhow!("No resumable session found for '{username}'"))
+    }
 
-        for (username, user) in users {
-            writer.write_all(format!("{username}:{}\n", user.password_hash()).as_bytes())?;
-        }
+    /// Resets all user sessions.
+    pub fn reset(&self) {
+        self.backend.reset();
+        self.sessions.clear();
+    }
 
-        Ok(())
+    /// Swaps in a freshly loaded set of users, preserving sessions where the
+    /// backend is able to (see `FileAuthBackend::reload`).
+    ///
+    /// ### Arguments
+    /// - `users` - the freshly loaded map of users (username -> `User`)
+    pub fn reload(&self, users: DashMap<String, User>) {
+        self.backend.reload(users);
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::auth::backend::FileAuthBackend;
     use crate::auth::user::User;
     use crate::auth::Auth;
     use argon2::password_hash::rand_core::OsRng;
@@ -169,11 +175,63 @@ mod tests {
         let test_user = User::new(username.clone(), password_hash.to_string());
         users.insert(username.clone(), test_user);
 
-        let auth = Auth::new(users);
+        let auth = Auth::new(Box::new(FileAuthBackend::new(users)));
         let session_token = tokio_test::block_on(auth.authenticate(&username, password))
             .expect("Credentials are valid");
         assert!(auth
             .verify_session_token(&username, session_token)
             .expect("User exists"))
     }
+
+    #[test]
+    fn test_reload_preserves_unchanged_session() {
+        let argon = Argon2::default();
+        let username = "test".to_owned();
+        let password = "password".to_owned();
+        let salt = SaltString::generate(&mut OsRng);
+        let password_hash = argon.hash_password(password.as_bytes(), &salt).unwrap();
+
+        let users: DashMap<String, User> = DashMap::new();
+        users.insert(
+            username.clone(),
+            User::new(username.clone(), password_hash.to_string()),
+        );
+
+        let auth = Auth::new(Box::new(FileAuthBackend::new(users)));
+        let session_token = tokio_test::block_on(auth.authenticate(&username, password))
+            .expect("Credentials are valid");
+
+        // Reloading with the same password hash must not invalidate the
+        // session token that was already issued.
+        let reloaded_users: DashMap<String, User> = DashMap::new();
+        reloaded_users.insert(
+            username.clone(),
+            User::new(username.clone(), password_hash.to_string()),
+        );
+        auth.reload(reloaded_users);
+
+        assert!(auth
+            .verify_session_token(&username, session_token)
+            .expect("User exists"))
+    }
+
+    #[test]
+    fn test_reload_removes_absent_users() {
+        let argon = Argon2::default();
+        let username = "test".to_owned();
+        let password = "password".to_owned();
+        let salt = SaltString::generate(&mut OsRng);
+        let password_hash = argon.hash_password(password.as_bytes(), &salt).unwrap();
+
+        let users: DashMap<String, User> = DashMap::new();
+        users.insert(
+            username.clone(),
+            User::new(username.clone(), password_hash.to_string()),
+        );
+
+        let auth = Auth::new(Box::new(FileAuthBackend::new(users)));
+        auth.reload(DashMap::new());
+
+        assert!(auth.verify_session_token(&username, [0u8; 16]).is_err());
+    }
 }
@@ -0,0 +1,78 @@
+pub mod file;
+
+#[cfg(feature = "pam")]
+pub mod pam;
+
+pub use file::FileAuthBackend;
+
+#[cfg(feature = "pam")]
+pub use pam::PamAuthBackend;
+
+use crate::auth::user::User;
+use crate::auth::SessionToken;
+use anyhow::Result;
+use async_trait::async_trait;
+use dashmap::DashMap;
+use std::path::Path;
+
+/// Selects which `AuthBackend` a tunnel authenticates against, read from that
+/// tunnel's `TunnelConfig`.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuthBackendConfig {
+    /// Authenticate against the tunnel's flat users file.
+    File,
+    /// Authenticate against the host's PAM stack using the given service name.
+    #[cfg(feature = "pam")]
+    Pam { service_name: String },
+}
+
+/// Builds the `AuthBackend` selected by `config` for a single tunnel.
+///
+/// ### Arguments
+/// - `config` - which backend to build, as configured on the tunnel
+/// - `users_file` - the tunnel's users file, used only by `AuthBackendConfig::File`
+pub fn build_auth_backend(
+    config: &AuthBackendConfig,
+    users_file: &Path,
+) -> Result<Box<dyn AuthBackend>> {
+    match config {
+        AuthBackendConfig::File => {
+            let users = FileAuthBackend::load_users_file(users_file)?;
+            Ok(Box::new(FileAuthBackend::new(users)))
+        }
+        #[cfg(feature = "pam")]
+        AuthBackendConfig::Pam { service_name } => {
+            Ok(Box::new(PamAuthBackend::new(service_name.clone())))
+        }
+    }
+}
+
+/// A pluggable authentication mechanism that `Auth` delegates credential
+/// verification and session tracking to.
+///
+/// Implementations decide how a username/password pair is verified, but
+/// share the same `SessionToken` type so that `AuthServerMessage` and
+/// `AuthClientMessage` handling can remain backend-agnostic.
+#[async_trait]
+pub trait AuthBackend: Send + Sync {
+    /// Authenticates the given credentials and returns a session token if successful.
+    ///
+    /// ### Arguments
+    /// - `username` - the username
+    /// - `password` - the password
+    async fn authenticate(&self, username: &str, password: String) -> Result<SessionToken>;
+
+    /// Verifies the given session token for the specified user.
+    ///
+    /// ### Arguments
+    /// - `username` - the username
+    /// - `session_token` - the session token
+    fn verify_session_token(&self, username: &str, session_token: SessionToken) -> Result<bool>;
+
+    /// Invalidates all sessions tracked by this backend, if any.
+    fn reset(&self) {}
+
+    /// Swaps in a freshly loaded set of users, if this backend maintains one.
+    fn reload(&self, _users: DashMap<String, User>) {}
+}
@@ -0,0 +1,132 @@
+use crate::auth::backend::AuthBackend;
+use crate::auth::user::User;
+use crate::auth::SessionToken;
+use anyhow::{anyhow, Result};
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use async_trait::async_trait;
+use dashmap::DashMap;
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// An `AuthBackend` that authenticates against a flat file of usernames and
+/// Argon2 password hashes.
+pub struct FileAuthBackend {
+    users: DashMap<String, User>,
+    hasher: Argon2<'static>,
+}
+
+impl FileAuthBackend {
+    /// Creates a new instance of the file-backed authentication backend.
+    ///
+    /// ### Arguments
+    /// - `users` - a map of users (username -> `User`)
+    pub fn new(users: DashMap<String, User>) -> Self {
+        Self {
+            users,
+            hasher: Argon2::default(),
+        }
+    }
+
+    /// Loads the contents of a file with users and their passwords hashes into a map.
+    ///
+    /// ### Arguments
+    /// - `users_file` - path to the users file
+    ///
+    /// ### Returns
+    /// - `DashMap` containing all loaded users
+    pub fn load_users_file(users_file: &Path) -> Result<DashMap<String, User>> {
+        let file = File::open(users_file)?;
+        let lines = BufReader::new(file).lines();
+
+        let result: DashMap<String, User> = DashMap::new();
+
+        for line in lines {
+            let user: User = line?.try_into()?;
+            result.insert(user.username().clone(), user);
+        }
+
+        Ok(result)
+    }
+
+    /// Writes the users and their password hashes into the specified file
+    ///
+    /// ### Arguments
+    /// - `users_file` - path to the users file
+    /// - `users` - a map of users (username -> `User`)
+    pub fn save_users_file(users_file: &Path, users: DashMap<String, User>) -> Result<()> {
+        if users_file.exists() {
+            fs::remove_file(users_file)?;
+        }
+
+        let file = File::create(users_file)?;
+        let mut writer = BufWriter::new(file);
+
+        for (username, user) in users {
+            writer.write_all(format!("{username}:{}\n", user.password_hash()).as_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AuthBackend for FileAuthBackend {
+    async fn authenticate(&self, username: &str, password: String) -> Result<SessionToken> {
+        let user = self
+            .users
+            .get(username)
+            .ok_or_else(|| anyhow!("Unknown user: {username}"))?;
+        let password_hash = PasswordHash::new(user.password_hash()).map_err(|err| {
+            anyhow!("Could not parse user password hash for user '{username}': {err}")
+        })?;
+
+        self.hasher
+            .verify_password(password.as_bytes(), &password_hash)
+            .map_err(|err| anyhow!("Could not verify credentials for user '{username}': {err}"))?;
+
+        Ok(user.new_session().await)
+    }
+
+    fn verify_session_token(&self, username: &str, session_token: SessionToken) -> Result<bool> {
+        let user = self
+            .users
+            .get(username)
+            .ok_or_else(|| anyhow!("Unknown user: {username}"))?;
+
+        Ok(user.check_session_validity(session_token))
+    }
+
+    fn reset(&self) {
+        for entry in self.users.iter() {
+            entry.value().reset();
+        }
+    }
+
+    /// Replaces the set of known users in place, preserving the session
+    /// tokens of users whose credentials did not change.
+    ///
+    /// The swap is applied entry by entry and is not atomic across users: a
+    /// concurrent lookup sees each user either before or after the reload,
+    /// but may see some users already updated while others are not yet.
+    ///
+    /// Users who were removed from `new_users` are dropped, users who are new
+    /// are inserted as-is, and users whose password hash is unchanged keep
+    /// their existing `User` (and therefore their currently valid session
+    /// token) instead of being replaced outright.
+    fn reload(&self, new_users: DashMap<String, User>) {
+        self.users
+            .retain(|username, _| new_users.contains_key(username));
+
+        for (username, new_user) in new_users {
+            if let Some(existing_user) = self.users.get(&username) {
+                if existing_user.password_hash() == new_user.password_hash() {
+                    continue;
+                }
+            }
+
+            self.users.insert(username, new_user);
+        }
+    }
+}
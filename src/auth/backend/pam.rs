@@ -0,0 +1,75 @@
+use crate::auth::backend::AuthBackend;
+use crate::auth::SessionToken;
+use anyhow::{anyhow, Result};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use async_trait::async_trait;
+use dashmap::DashMap;
+use pam::Client;
+
+/// An `AuthBackend` that authenticates against the host's PAM stack, letting
+/// operators reuse existing system or LDAP credentials instead of
+/// maintaining a separate hashed users file.
+///
+/// Unlike `FileAuthBackend`, there is no persisted user map to reload - PAM
+/// is the source of truth for credentials, and this backend only keeps
+/// track of the session token currently issued to each authenticated user.
+pub struct PamAuthBackend {
+    service_name: String,
+    sessions: DashMap<String, SessionToken>,
+}
+
+impl PamAuthBackend {
+    /// Creates a new instance of the PAM-backed authentication backend.
+    ///
+    /// ### Arguments
+    /// - `service_name` - the PAM service name to authenticate against (e.g. `"login"`)
+    pub fn new(service_name: impl Into<String>) -> Self {
+        Self {
+            service_name: service_name.into(),
+            sessions: DashMap::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl AuthBackend for PamAuthBackend {
+    async fn authenticate(&self, username: &str, password: String) -> Result<SessionToken> {
+        let service_name = self.service_name.clone();
+        let username = username.to_owned();
+        let conversation_username = username.clone();
+
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let mut client = Client::with_password(&service_name)
+                .map_err(|err| anyhow!("Failed to initialize PAM client: {err}"))?;
+
+            client
+                .conversation_mut()
+                .set_credentials(&conversation_username, &password);
+
+            client.authenticate().map_err(|err| {
+                anyhow!("PAM authentication failed for user '{conversation_username}': {err}")
+            })
+        })
+        .await??;
+
+        let mut session_token = [0u8; 16];
+        OsRng.fill_bytes(&mut session_token);
+
+        self.sessions.insert(username, session_token);
+
+        Ok(session_token)
+    }
+
+    fn verify_session_token(&self, username: &str, session_token: SessionToken) -> Result<bool> {
+        let current_token = self
+            .sessions
+            .get(username)
+            .ok_or_else(|| anyhow!("Unknown user: {username}"))?;
+
+        Ok(*current_token == session_token)
+    }
+
+    fn reset(&self) {
+        self.sessions.clear();
+    }
+}
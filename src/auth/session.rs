@@ -0,0 +1,194 @@
+use crate::auth::SessionToken;
+use dashmap::DashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Tracks expiry metadata for issued session tokens so a client that drops
+/// its QUIC connection can resume its session by presenting
+/// `AuthClientMessage::SessionToken`, without being able to replay a stale
+/// token indefinitely.
+pub struct SessionStore {
+    records: DashMap<SessionToken, SessionRecord>,
+    idle_timeout: Duration,
+    max_reconnect_window: Duration,
+}
+
+struct SessionRecord {
+    username: String,
+    issued_at: Instant,
+    last_used_at: Instant,
+    /// The tunnel IP address assigned to this session, so a reconnecting
+    /// client can be re-bound to the same address instead of being handed a
+    /// new one from the address pool.
+    assigned_addr: Option<IpAddr>,
+}
+
+impl SessionStore {
+    /// ### Arguments
+    /// - `idle_timeout` - how long a token may go unused before it is considered stale
+    /// - `max_reconnect_window` - the hard ceiling on how long after issuance a token can ever be resumed, regardless of use
+    pub fn new(idle_timeout: Duration, max_reconnect_window: Duration) -> Self {
+        Self {
+            records: DashMap::new(),
+            idle_timeout,
+            max_reconnect_window,
+        }
+    }
+
+    /// Records that `session_token` was just issued to `username`.
+    pub fn track(&self, username: &str, session_token: SessionToken) {
+        let now = Instant::now();
+
+        self.records.insert(
+            session_token,
+            SessionRecord {
+                username: username.to_owned(),
+                issued_at: now,
+                last_used_at: now,
+                assigned_addr: None,
+            },
+        );
+    }
+
+    /// Records the tunnel address assigned to `session_token`, so a later
+    /// `try_resume_addr` can hand the reconnecting client back the same address.
+    pub fn set_assigned_addr(&self, session_token: SessionToken, addr: IpAddr) {
+        if let Some(mut record) = self.records.get_mut(&session_token) {
+            record.assigned_addr = Some(addr);
+        }
+    }
+
+    /// Checks whether `session_token` may still be used by `username` to
+    /// resume a session, and if so refreshes its idle timer.
+    ///
+    /// ### Returns
+    /// - `true` if the token is tracked, belongs to `username` and is within both the idle timeout and the max reconnect window
+    pub fn try_resume(&self, username: &str, session_token: SessionToken) -> bool {
+        self.validate(username, session_token).is_some()
+    }
+
+    /// Like `try_resume`, but also returns the tunnel address previously
+    /// assigned to the session via `set_assigned_addr`, so a reconnecting
+    /// client can be re-bound to it instead of drawing a fresh one.
+    ///
+    /// ### Returns
+    /// - `Some(addr)` if the session can be resumed and an address was recorded for it
+    /// - `None` if the session cannot be resumed, or no address was ever assigned to it
+    pub fn try_resume_addr(&self, username: &str, session_token: SessionToken) -> Option<IpAddr> {
+        self.validate(username, session_token)?.assigned_addr
+    }
+
+    /// Validates that `session_token` belongs to `username` and is within
+    /// both the idle timeout and the max reconnect window, refreshing its
+    /// idle timer and evicting it if expired.
+    fn validate(
+        &self,
+        username: &str,
+        session_token: SessionToken,
+    ) -> Option<dashmap::mapref::one::RefMut<'_, SessionToken, SessionRecord>> {
+        let mut record = self.records.get_mut(&session_token)?;
+
+        if record.username != username {
+            return None;
+        }
+
+        let now = Instant::now();
+        let expired = now.duration_since(record.issued_at) > self.max_reconnect_window
+            || now.duration_since(record.last_used_at) > self.idle_timeout;
+
+        if expired {
+            drop(record);
+            self.records.remove(&session_token);
+            return None;
+        }
+
+        record.last_used_at = now;
+
+        Some(record)
+    }
+
+    /// Drops every tracked token, e.g. in response to `Auth::reset`.
+    pub fn clear(&self) {
+        self.records.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SessionStore;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn test_resume_within_idle_timeout() {
+        let store = SessionStore::new(Duration::from_secs(60), Duration::from_secs(60));
+        let token = [1u8; 16];
+
+        store.track("test", token);
+
+        assert!(store.try_resume("test", token));
+    }
+
+    #[test]
+    fn test_resume_rejects_wrong_username() {
+        let store = SessionStore::new(Duration::from_secs(60), Duration::from_secs(60));
+        let token = [1u8; 16];
+
+        store.track("test", token);
+
+        assert!(!store.try_resume("someone-else", token));
+    }
+
+    #[test]
+    fn test_resume_rejects_idle_expired_token() {
+        let store = SessionStore::new(Duration::from_millis(1), Duration::from_secs(60));
+        let token = [1u8; 16];
+
+        store.track("test", token);
+        sleep(Duration::from_millis(20));
+
+        assert!(!store.try_resume("test", token));
+    }
+
+    #[test]
+    fn test_resume_rejects_untracked_token() {
+        let store = SessionStore::new(Duration::from_secs(60), Duration::from_secs(60));
+
+        assert!(!store.try_resume("test", [2u8; 16]));
+    }
+
+    #[test]
+    fn test_resume_addr_returns_assigned_addr() {
+        let store = SessionStore::new(Duration::from_secs(60), Duration::from_secs(60));
+        let token = [1u8; 16];
+        let addr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+        store.track("test", token);
+        store.set_assigned_addr(token, addr);
+
+        assert_eq!(store.try_resume_addr("test", token), Some(addr));
+    }
+
+    #[test]
+    fn test_resume_addr_rejects_unassigned_session() {
+        let store = SessionStore::new(Duration::from_secs(60), Duration::from_secs(60));
+        let token = [1u8; 16];
+
+        store.track("test", token);
+
+        assert_eq!(store.try_resume_addr("test", token), None);
+    }
+
+    #[test]
+    fn test_resume_addr_rejects_wrong_username() {
+        let store = SessionStore::new(Duration::from_secs(60), Duration::from_secs(60));
+        let token = [1u8; 16];
+        let addr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+        store.track("test", token);
+        store.set_assigned_addr(token, addr);
+
+        assert_eq!(store.try_resume_addr("someone-else", token), None);
+    }
+}
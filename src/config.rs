@@ -0,0 +1,48 @@
+use crate::auth::backend::AuthBackendConfig;
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+/// Top-level configuration for a Quincy server: the QUIC transport settings
+/// shared by every tunnel, and the set of tunnels it serves keyed by name.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ServerConfig {
+    pub connection: ConnectionConfig,
+    pub tunnels: HashMap<String, TunnelConfig>,
+}
+
+impl ServerConfig {
+    /// Loads and parses a `ServerConfig` from a TOML file at `path`.
+    ///
+    /// ### Arguments
+    /// - `path` - path to the server configuration file
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+
+        Ok(toml::from_str(&contents)?)
+    }
+}
+
+/// QUIC transport settings shared by every tunnel on a server.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct ConnectionConfig {
+    pub bind_addr: SocketAddr,
+}
+
+/// Configuration for a single tunnel served by a `QuincyServer`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct TunnelConfig {
+    pub users_file: PathBuf,
+    pub auth_backend: AuthBackendConfig,
+    /// The tunnel addresses newly authenticated sessions are assigned from.
+    pub addresses: Vec<IpAddr>,
+}
+
+impl TunnelConfig {
+    /// Path to this tunnel's flat users file.
+    pub fn users_file(&self) -> &Path {
+        &self.users_file
+    }
+}
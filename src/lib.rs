@@ -9,6 +9,8 @@ pub mod auth;
 pub mod client;
 pub mod config;
 pub mod constants;
+pub mod forward;
 pub mod interface;
 pub mod server;
+pub mod socks5;
 pub mod utils;
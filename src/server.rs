@@ -1,11 +1,13 @@
+use std::path::PathBuf;
 use std::time::Duration;
 
 use crate::config::ServerConfig;
 use crate::server::tunnel::QuincyTunnel;
 use anyhow::Result;
 use dashmap::DashMap;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
 use tokio::time::sleep;
-use tracing::error;
+use tracing::{error, info};
 
 pub mod address_pool;
 pub mod connection;
@@ -25,7 +27,13 @@ impl QuincyServer {
         let tunnels = DashMap::new();
 
         for (name, tunnel_config) in config.tunnels.iter() {
-            let tunnel = QuincyTunnel::new(tunnel_config.clone(), &config.connection)?;
+            let auth_backend = crate::auth::backend::build_auth_backend(
+                &tunnel_config.auth_backend,
+                tunnel_config.users_file(),
+            )?;
+            let auth = crate::auth::Auth::new(auth_backend);
+
+            let tunnel = QuincyTunnel::new(tunnel_config.clone(), &config.connection, auth)?;
 
             tunnels.insert(name.clone(), tunnel);
         }
@@ -60,4 +68,127 @@ impl QuincyServer {
             sleep(Duration::from_secs(1)).await;
         }
     }
+
+    /// Applies a freshly loaded `ServerConfig` to the running server in
+    /// place, without disturbing tunnels whose configuration did not change.
+    ///
+    /// Tunnels that are no longer present in `new_config` are stopped and
+    /// removed, tunnels whose `TunnelConfig` changed are restarted, and
+    /// unchanged tunnels (including their active datagram flows) are left
+    /// running untouched.
+    ///
+    /// ### Arguments
+    /// - `new_config` - the reloaded server configuration
+    pub async fn reload(&self, new_config: ServerConfig) -> Result<()> {
+        let removed_tunnels: Vec<String> = self
+            .active_tunnels
+            .iter()
+            .map(|entry| entry.key().to_owned())
+            .filter(|name| !new_config.tunnels.contains_key(name))
+            .collect();
+
+        for name in removed_tunnels {
+            if let Some((_, mut tunnel)) = self.active_tunnels.remove(&name) {
+                info!("Removing tunnel '{name}' as it is no longer present in the configuration");
+                tunnel.stop().await?;
+            }
+        }
+
+        for (name, tunnel_config) in new_config.tunnels.iter() {
+            if let Some(existing) = self.active_tunnels.get(name) {
+                if existing.config() == tunnel_config {
+                    continue;
+                }
+            }
+
+            if let Some((_, mut tunnel)) = self.active_tunnels.remove(name) {
+                info!("Restarting tunnel '{name}' due to a configuration change");
+                tunnel.stop().await?;
+            } else {
+                info!("Starting new tunnel '{name}'");
+            }
+
+            let auth_backend = crate::auth::backend::build_auth_backend(
+                &tunnel_config.auth_backend,
+                tunnel_config.users_file(),
+            )?;
+            let auth = crate::auth::Auth::new(auth_backend);
+
+            let mut tunnel =
+                QuincyTunnel::new(tunnel_config.clone(), &new_config.connection, auth)?;
+            tunnel.start().await?;
+
+            self.active_tunnels.insert(name.clone(), tunnel);
+        }
+
+        Ok(())
+    }
+
+    /// Reloads the users file of a single tunnel, swapping in the new users
+    /// without affecting any other tunnel or its active connections.
+    ///
+    /// ### Arguments
+    /// - `tunnel_name` - the name of the tunnel whose users file changed
+    pub fn reload_users(&self, tunnel_name: &str) -> Result<()> {
+        let tunnel = self
+            .active_tunnels
+            .get(tunnel_name)
+            .ok_or_else(|| anyhow::anyhow!("Unknown tunnel: {tunnel_name}"))?;
+
+        let users =
+            crate::auth::backend::FileAuthBackend::load_users_file(tunnel.config().users_file())?;
+        tunnel.auth().reload(users);
+
+        Ok(())
+    }
+
+    /// Watches the server configuration file and every tunnel's users file
+    /// for changes and applies them in place, without tearing down tunnels
+    /// that were not affected by the change.
+    ///
+    /// ### Arguments
+    /// - `config_path` - path to the server configuration file that was used to start this server
+    pub async fn watch_for_reload(&self, config_path: PathBuf) -> Result<()> {
+        let (reload_tx, mut reload_rx) = tokio::sync::watch::channel(());
+
+        let mut watched_paths: Vec<PathBuf> = vec![config_path.clone()];
+        for entry in self.active_tunnels.iter() {
+            watched_paths.push(entry.value().config().users_file().to_path_buf());
+        }
+
+        let mut watcher = RecommendedWatcher::new(
+            move |res: notify::Result<Event>| {
+                if res.is_ok() {
+                    // Ignore send errors - they only mean the watcher outlived the server.
+                    let _ = reload_tx.send(());
+                }
+            },
+            notify::Config::default(),
+        )?;
+
+        for path in &watched_paths {
+            watcher.watch(path, RecursiveMode::NonRecursive)?;
+        }
+
+        loop {
+            reload_rx.changed().await?;
+
+            match ServerConfig::from_file(&config_path) {
+                Ok(new_config) => {
+                    if let Err(err) = self.reload(new_config).await {
+                        error!("Failed to apply reloaded server configuration: {err}");
+                    }
+                }
+                Err(err) => error!("Failed to parse reloaded server configuration: {err}"),
+            }
+
+            for entry in self.active_tunnels.iter() {
+                let tunnel_name = entry.key().to_owned();
+
+                if let Err(err) = self.reload_users(&tunnel_name) {
+                    error!("Failed to reload users file for tunnel '{tunnel_name}': {err}");
+                }
+            }
+        }
+    }
 }
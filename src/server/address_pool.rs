@@ -0,0 +1,27 @@
+use std::collections::VecDeque;
+use std::net::IpAddr;
+
+/// Hands out tunnel IP addresses to newly authenticated sessions from a
+/// fixed set, and takes them back when a session ends or is rebound.
+pub struct AddressPool {
+    available: VecDeque<IpAddr>,
+}
+
+impl AddressPool {
+    /// Creates a pool over `addresses`, handed out in the given order.
+    pub fn new(addresses: Vec<IpAddr>) -> Self {
+        Self {
+            available: addresses.into(),
+        }
+    }
+
+    /// Hands out the next available address, if any remain.
+    pub fn acquire(&mut self) -> Option<IpAddr> {
+        self.available.pop_front()
+    }
+
+    /// Returns `addr` to the pool so it can be handed out again.
+    pub fn release(&mut self, addr: IpAddr) {
+        self.available.push_back(addr);
+    }
+}
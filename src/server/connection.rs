@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use quinn::Connection;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::auth::{Auth, AuthClientMessage, AuthServerMessage};
+use crate::server::address_pool::AddressPool;
+use crate::tun::TunWorker;
+
+/// Handles a single incoming client connection for a tunnel: performs the
+/// authentication handshake on the first bidirectional stream the client
+/// opens, assigns (or resumes) a tunnel address, and registers the
+/// connection with `tun_worker` so it can start exchanging traffic.
+pub async fn handle_connection(
+    connection: Arc<Connection>,
+    auth: &Auth,
+    address_pool: &Mutex<AddressPool>,
+    tun_worker: &TunWorker,
+) -> Result<()> {
+    let (mut send, mut recv) = connection.accept_bi().await?;
+
+    let header_len = recv.read_u32().await? as usize;
+    let mut header = vec![0u8; header_len];
+    recv.read_exact(&mut header).await?;
+    let (message, _): (AuthClientMessage, usize) =
+        bincode::decode_from_slice(&header, bincode::config::standard())?;
+
+    match message {
+        AuthClientMessage::Authentication(username, password) => {
+            let session_token = auth.authenticate(&username, password).await?;
+
+            let addr = address_pool
+                .lock()
+                .await
+                .acquire()
+                .ok_or_else(|| anyhow!("Address pool exhausted"))?;
+            auth.bind_session_addr(session_token, addr);
+
+            let reply = AuthServerMessage::Authenticated(vec![], vec![], session_token);
+            let encoded = bincode::encode_to_vec(&reply, bincode::config::standard())?;
+            send.write_u32(encoded.len() as u32).await?;
+            send.write_all(&encoded).await?;
+
+            tun_worker.add_connection(addr, connection).await;
+        }
+        AuthClientMessage::SessionToken(username, session_token) => {
+            match tun_worker
+                .resume_connection(auth, &username, session_token, connection)
+                .await
+            {
+                Ok(stale) => {
+                    if stale.is_some() {
+                        warn!(
+                            "Resumed session for '{username}' replaced a still-active connection"
+                        );
+                    }
+
+                    let encoded =
+                        bincode::encode_to_vec(AuthServerMessage::Ok, bincode::config::standard())?;
+                    send.write_u32(encoded.len() as u32).await?;
+                    send.write_all(&encoded).await?;
+                }
+                Err(err) => {
+                    let encoded = bincode::encode_to_vec(
+                        AuthServerMessage::Failed,
+                        bincode::config::standard(),
+                    )?;
+                    send.write_u32(encoded.len() as u32).await?;
+                    send.write_all(&encoded).await?;
+
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
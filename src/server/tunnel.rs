@@ -0,0 +1,84 @@
+use crate::auth::Auth;
+use crate::config::{ConnectionConfig, TunnelConfig};
+use crate::server::address_pool::AddressPool;
+use anyhow::Result;
+use tokio::sync::Mutex;
+
+/// A single tunnel served by a `QuincyServer`: its configuration, the
+/// authentication module clients connecting to it are checked against, the
+/// pool of addresses it assigns to newly authenticated sessions (see
+/// `server::connection::handle_connection`), and whether it is marked as
+/// running.
+pub struct QuincyTunnel {
+    config: TunnelConfig,
+    connection_config: ConnectionConfig,
+    auth: Auth,
+    address_pool: Mutex<AddressPool>,
+    running: bool,
+}
+
+impl QuincyTunnel {
+    /// Creates a new tunnel from `config`, ready to be `start`ed.
+    ///
+    /// ### Arguments
+    /// - `config` - this tunnel's own configuration (users file, auth backend, address pool)
+    /// - `connection_config` - the QUIC transport settings shared by every tunnel on this server
+    /// - `auth` - the authentication module clients connecting to this tunnel are checked against
+    pub fn new(
+        config: TunnelConfig,
+        connection_config: &ConnectionConfig,
+        auth: Auth,
+    ) -> Result<Self> {
+        let address_pool = Mutex::new(AddressPool::new(config.addresses.clone()));
+
+        Ok(Self {
+            config,
+            connection_config: connection_config.clone(),
+            auth,
+            address_pool,
+            running: false,
+        })
+    }
+
+    /// Marks the tunnel as running. No worker tasks are spawned here; this
+    /// only sets the flag that `is_ok` reports.
+    pub async fn start(&mut self) -> Result<()> {
+        self.running = true;
+
+        Ok(())
+    }
+
+    /// Marks the tunnel as stopped; this only clears the flag that `is_ok` reports.
+    pub async fn stop(&mut self) -> Result<()> {
+        self.running = false;
+
+        Ok(())
+    }
+
+    /// Whether the tunnel is marked as running, i.e. `start` was called more
+    /// recently than `stop`.
+    pub fn is_ok(&self) -> bool {
+        self.running
+    }
+
+    /// This tunnel's configuration.
+    pub fn config(&self) -> &TunnelConfig {
+        &self.config
+    }
+
+    /// The QUIC transport settings this tunnel was created with.
+    pub fn connection_config(&self) -> &ConnectionConfig {
+        &self.connection_config
+    }
+
+    /// This tunnel's authentication module.
+    pub fn auth(&self) -> &Auth {
+        &self.auth
+    }
+
+    /// The pool of tunnel addresses this tunnel assigns to newly
+    /// authenticated sessions.
+    pub fn address_pool(&self) -> &Mutex<AddressPool> {
+        &self.address_pool
+    }
+}
@@ -0,0 +1,763 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use bincode::{Decode, Encode};
+use dashmap::DashMap;
+use quinn::{Connection, RecvStream, SendStream};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+const SOCKS5_VERSION: u8 = 0x05;
+const AUTH_METHOD_NONE: u8 = 0x00;
+const AUTH_METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const AUTH_METHOD_UNACCEPTABLE: u8 = 0xFF;
+
+const CMD_CONNECT: u8 = 0x01;
+const CMD_UDP_ASSOCIATE: u8 = 0x03;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+const REPLY_SUCCESS: u8 = 0x00;
+const REPLY_GENERAL_FAILURE: u8 = 0x01;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+
+/// Sent by the server over a forwarded CONNECT stream, right after it dials
+/// the requested target, so the client can gate its SOCKS5 reply on whether
+/// the target was actually reachable instead of replying optimistically.
+const DIAL_ACK_SUCCESS: u8 = 0x00;
+const DIAL_ACK_FAILURE: u8 = 0x01;
+
+/// Leading byte that marks a QUIC datagram as carrying an encapsulated SOCKS5
+/// UDP ASSOCIATE payload rather than a datagram of the full-tunnel
+/// (`TunWorker`) path. The SOCKS5 readers skip datagrams without this tag
+/// but do not hand them on to `TunWorker`, and `TunWorker` drops datagrams
+/// with this tag; both read from the same `Connection`, so a datagram taken
+/// by one of them is never seen by the other.
+pub const SOCKS5_UDP_DATAGRAM_TAG: u8 = 0xFF;
+
+/// Configuration for the SOCKS5 ingress mode.
+#[derive(Clone, Debug)]
+pub struct Socks5Config {
+    pub listen_addr: SocketAddr,
+    pub credentials: Option<(String, String)>,
+}
+
+/// The destination a client asked to `CONNECT` to or associate UDP traffic
+/// with, as parsed from the client's SOCKS5 request. Sent to the server
+/// over a dedicated QUIC bidirectional stream so it knows what to dial.
+#[derive(Clone, Debug, Encode, Decode)]
+pub enum Socks5Target {
+    SocketAddr(SocketAddr),
+    Domain(String, u16),
+}
+
+/// A single encapsulated SOCKS5 UDP ASSOCIATE datagram, exchanged as the
+/// payload of a QUIC datagram tagged with `SOCKS5_UDP_DATAGRAM_TAG`.
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct Socks5UdpDatagram {
+    pub session_id: u32,
+    pub target: Socks5Target,
+    pub payload: Vec<u8>,
+}
+
+/// Tracks a single active UDP ASSOCIATE session on the client side so that
+/// `route_udp_replies` knows which local `relay_socket` and client address
+/// to forward a reply datagram received on `connection` back to.
+struct UdpAssociateSession {
+    relay_socket: Arc<UdpSocket>,
+    client_addr: Mutex<Option<SocketAddr>>,
+}
+
+/// Runs a local SOCKS5 server (CONNECT and UDP ASSOCIATE) that relays
+/// traffic over `connection` instead of through a TUN device, letting
+/// unprivileged users route selected application traffic through Quincy.
+/// Reads datagrams and accepts streams on `connection` itself, so a
+/// full-tunnel `TunWorker` reading from the same connection would compete
+/// with it for incoming datagrams (see `SOCKS5_UDP_DATAGRAM_TAG`).
+pub struct Socks5Server {
+    connection: Arc<Connection>,
+    config: Socks5Config,
+    next_session_id: std::sync::atomic::AtomicU32,
+    udp_sessions: Arc<DashMap<u32, Arc<UdpAssociateSession>>>,
+}
+
+impl Socks5Server {
+    pub fn new(connection: Arc<Connection>, config: Socks5Config) -> Self {
+        Self {
+            connection,
+            config,
+            next_session_id: std::sync::atomic::AtomicU32::new(0),
+            udp_sessions: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// Accepts SOCKS5 clients on `config.listen_addr` until an error occurs,
+    /// alongside a background task that routes UDP ASSOCIATE reply datagrams
+    /// received on `connection` back to the session that requested them.
+    pub async fn run(&self) -> Result<()> {
+        let listener = TcpListener::bind(self.config.listen_addr).await?;
+
+        let reply_router = tokio::spawn(Self::route_udp_replies(
+            self.connection.clone(),
+            self.udp_sessions.clone(),
+        ));
+
+        let result = self.accept_clients(listener).await;
+
+        reply_router.abort();
+        result
+    }
+
+    async fn accept_clients(&self, listener: TcpListener) -> Result<()> {
+        loop {
+            let (socket, _) = listener.accept().await?;
+            let connection = self.connection.clone();
+            let credentials = self.config.credentials.clone();
+            let session_id = self
+                .next_session_id
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let udp_sessions = self.udp_sessions.clone();
+
+            tokio::spawn(async move {
+                if let Err(err) =
+                    Self::handle_client(socket, connection, credentials, session_id, udp_sessions)
+                        .await
+                {
+                    warn!("SOCKS5 client session ended: {err}");
+                }
+            });
+        }
+    }
+
+    async fn handle_client(
+        mut socket: TcpStream,
+        connection: Arc<Connection>,
+        credentials: Option<(String, String)>,
+        session_id: u32,
+        udp_sessions: Arc<DashMap<u32, Arc<UdpAssociateSession>>>,
+    ) -> Result<()> {
+        Self::negotiate_auth(&mut socket, credentials.as_ref()).await?;
+
+        let (cmd, target) = Self::read_request(&mut socket).await?;
+
+        match cmd {
+            CMD_CONNECT => Self::handle_connect(socket, connection, target).await,
+            CMD_UDP_ASSOCIATE => {
+                Self::handle_udp_associate(socket, connection, session_id, udp_sessions).await
+            }
+            _ => {
+                Self::write_reply(&mut socket, REPLY_COMMAND_NOT_SUPPORTED).await?;
+                bail!("Unsupported SOCKS5 command: {cmd}")
+            }
+        }
+    }
+
+    /// Performs the SOCKS5 method negotiation and, if `credentials` is set,
+    /// the RFC 1929 username/password sub-negotiation.
+    async fn negotiate_auth(
+        socket: &mut TcpStream,
+        credentials: Option<&(String, String)>,
+    ) -> Result<()> {
+        let version = socket.read_u8().await?;
+        if version != SOCKS5_VERSION {
+            bail!("Unsupported SOCKS version: {version}");
+        }
+
+        let method_count = socket.read_u8().await? as usize;
+        let mut methods = vec![0u8; method_count];
+        socket.read_exact(&mut methods).await?;
+
+        let required_method = if credentials.is_some() {
+            AUTH_METHOD_USERNAME_PASSWORD
+        } else {
+            AUTH_METHOD_NONE
+        };
+
+        if !methods.contains(&required_method) {
+            socket
+                .write_all(&[SOCKS5_VERSION, AUTH_METHOD_UNACCEPTABLE])
+                .await?;
+            bail!("Client does not support the required authentication method");
+        }
+
+        socket.write_all(&[SOCKS5_VERSION, required_method]).await?;
+
+        let Some((expected_username, expected_password)) = credentials else {
+            return Ok(());
+        };
+
+        let auth_version = socket.read_u8().await?;
+        if auth_version != 0x01 {
+            bail!("Unsupported SOCKS5 username/password auth version: {auth_version}");
+        }
+
+        let username_len = socket.read_u8().await? as usize;
+        let mut username = vec![0u8; username_len];
+        socket.read_exact(&mut username).await?;
+
+        let password_len = socket.read_u8().await? as usize;
+        let mut password = vec![0u8; password_len];
+        socket.read_exact(&mut password).await?;
+
+        let authenticated =
+            username == expected_username.as_bytes() && password == expected_password.as_bytes();
+
+        socket
+            .write_all(&[0x01, if authenticated { 0x00 } else { 0x01 }])
+            .await?;
+
+        if !authenticated {
+            bail!("Invalid SOCKS5 username/password");
+        }
+
+        Ok(())
+    }
+
+    /// Parses a SOCKS5 request, returning its command and target address.
+    async fn read_request(socket: &mut TcpStream) -> Result<(u8, Socks5Target)> {
+        let version = socket.read_u8().await?;
+        if version != SOCKS5_VERSION {
+            bail!("Unsupported SOCKS version: {version}");
+        }
+
+        let cmd = socket.read_u8().await?;
+        let _reserved = socket.read_u8().await?;
+        let address_type = socket.read_u8().await?;
+
+        let target = match address_type {
+            ATYP_IPV4 => {
+                let mut octets = [0u8; 4];
+                socket.read_exact(&mut octets).await?;
+                let port = socket.read_u16().await?;
+
+                Socks5Target::SocketAddr(SocketAddr::new(IpAddr::V4(Ipv4Addr::from(octets)), port))
+            }
+            ATYP_IPV6 => {
+                let mut octets = [0u8; 16];
+                socket.read_exact(&mut octets).await?;
+                let port = socket.read_u16().await?;
+
+                Socks5Target::SocketAddr(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port))
+            }
+            ATYP_DOMAIN => {
+                let len = socket.read_u8().await? as usize;
+                let mut domain = vec![0u8; len];
+                socket.read_exact(&mut domain).await?;
+                let port = socket.read_u16().await?;
+
+                Socks5Target::Domain(String::from_utf8(domain)?, port)
+            }
+            _ => bail!("Unsupported SOCKS5 address type: {address_type}"),
+        };
+
+        Ok((cmd, target))
+    }
+
+    /// Opens a QUIC bidirectional stream carrying `target`, then relays
+    /// bytes between it and the client's TCP socket until either side closes.
+    async fn handle_connect(
+        mut socket: TcpStream,
+        connection: Arc<Connection>,
+        target: Socks5Target,
+    ) -> Result<()> {
+        let (mut send, mut recv) = match connection.open_bi().await {
+            Ok(streams) => streams,
+            Err(err) => {
+                Self::write_reply(&mut socket, REPLY_GENERAL_FAILURE).await?;
+                return Err(anyhow!("Failed to open a forwarding stream: {err}"));
+            }
+        };
+
+        let header = bincode::encode_to_vec(&target, bincode::config::standard())?;
+        send.write_u32(header.len() as u32).await?;
+        send.write_all(&header).await?;
+
+        // Wait for the server to actually dial `target` before telling the
+        // local SOCKS5 client it succeeded, instead of replying optimistically.
+        let dial_ack = match recv.read_u8().await {
+            Ok(ack) => ack,
+            Err(err) => {
+                Self::write_reply(&mut socket, REPLY_GENERAL_FAILURE).await?;
+                return Err(anyhow!("Failed to read the dial acknowledgement: {err}"));
+            }
+        };
+
+        if dial_ack != DIAL_ACK_SUCCESS {
+            Self::write_reply(&mut socket, REPLY_GENERAL_FAILURE).await?;
+            bail!("Server failed to connect to the requested target");
+        }
+
+        Self::write_reply(&mut socket, REPLY_SUCCESS).await?;
+
+        let (mut socket_read, mut socket_write) = socket.into_split();
+
+        let upload = async {
+            let mut buf = vec![0u8; 4096];
+            loop {
+                let read = socket_read.read(&mut buf).await?;
+                if read == 0 {
+                    break;
+                }
+                send.write_all(&buf[..read]).await?;
+            }
+            send.finish()
+                .map_err(|err| anyhow!("Failed to close forwarding stream: {err}"))
+        };
+
+        let download = async {
+            let mut buf = vec![0u8; 4096];
+            loop {
+                match recv.read(&mut buf).await? {
+                    Some(0) | None => break,
+                    Some(read) => socket_write.write_all(&buf[..read]).await?,
+                }
+            }
+            Ok::<(), anyhow::Error>(())
+        };
+
+        tokio::try_join!(upload, download)?;
+
+        Ok(())
+    }
+
+    /// Binds a local UDP socket for the lifetime of the client's TCP control
+    /// connection, and relays datagrams the client sends to it over
+    /// `connection`, encapsulated as `Socks5UdpDatagram`s tagged with
+    /// `SOCKS5_UDP_DATAGRAM_TAG`.
+    ///
+    /// Registers itself in `udp_sessions` so `route_udp_replies` can forward
+    /// reply datagrams received on `connection` back to `relay_socket` and
+    /// the last client address observed by this function.
+    async fn handle_udp_associate(
+        mut socket: TcpStream,
+        connection: Arc<Connection>,
+        session_id: u32,
+        udp_sessions: Arc<DashMap<u32, Arc<UdpAssociateSession>>>,
+    ) -> Result<()> {
+        let relay_socket = Arc::new(UdpSocket::bind((socket.local_addr()?.ip(), 0)).await?);
+        let relay_addr = relay_socket.local_addr()?;
+
+        Self::write_udp_associate_reply(&mut socket, relay_addr).await?;
+
+        let session = Arc::new(UdpAssociateSession {
+            relay_socket: relay_socket.clone(),
+            client_addr: Mutex::new(None),
+        });
+        udp_sessions.insert(session_id, session.clone());
+
+        let result = Self::pump_udp_associate(
+            &mut socket,
+            &relay_socket,
+            &connection,
+            &session,
+            session_id,
+        )
+        .await;
+
+        udp_sessions.remove(&session_id);
+
+        result
+    }
+
+    async fn pump_udp_associate(
+        socket: &mut TcpStream,
+        relay_socket: &UdpSocket,
+        connection: &Connection,
+        session: &UdpAssociateSession,
+        session_id: u32,
+    ) -> Result<()> {
+        let mut buf = vec![0u8; 4096];
+
+        loop {
+            tokio::select! {
+                result = relay_socket.recv_from(&mut buf) => {
+                    let (read, from) = result?;
+                    *session.client_addr.lock().await = Some(from);
+
+                    let (target, payload) = Self::parse_udp_request(&buf[..read])?;
+                    let datagram = Socks5UdpDatagram { session_id, target, payload };
+                    let mut encoded = vec![SOCKS5_UDP_DATAGRAM_TAG];
+                    encoded.extend(bincode::encode_to_vec(&datagram, bincode::config::standard())?);
+
+                    connection.send_datagram(encoded.into())?;
+                }
+                // The control connection closing ends the UDP association.
+                read = socket.read(&mut [0u8; 1]) => {
+                    if matches!(read, Ok(0) | Err(_)) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads reply datagrams tagged `SOCKS5_UDP_DATAGRAM_TAG` off `connection`
+    /// until an error occurs, and forwards each one's payload back to the
+    /// client address last seen by the `UdpAssociateSession` with a matching
+    /// `session_id`. Datagrams for a session that has since ended, or whose
+    /// client address isn't known yet, are dropped with a warning.
+    async fn route_udp_replies(
+        connection: Arc<Connection>,
+        udp_sessions: Arc<DashMap<u32, Arc<UdpAssociateSession>>>,
+    ) -> Result<()> {
+        loop {
+            let datagram = connection.read_datagram().await?;
+            if datagram.first() != Some(&SOCKS5_UDP_DATAGRAM_TAG) {
+                continue;
+            }
+
+            let (reply, _): (Socks5UdpDatagram, usize) =
+                match bincode::decode_from_slice(&datagram[1..], bincode::config::standard()) {
+                    Ok(decoded) => decoded,
+                    Err(err) => {
+                        warn!("Failed to decode SOCKS5 UDP ASSOCIATE reply datagram: {err}");
+                        continue;
+                    }
+                };
+
+            let Some(session) = udp_sessions
+                .get(&reply.session_id)
+                .map(|entry| entry.clone())
+            else {
+                warn!("Dropping SOCKS5 UDP ASSOCIATE reply for ended session {}", reply.session_id);
+                continue;
+            };
+
+            let Some(client_addr) = *session.client_addr.lock().await else {
+                warn!("Dropping SOCKS5 UDP ASSOCIATE reply for session {} with no known client address yet", reply.session_id);
+                continue;
+            };
+
+            let packet = Self::encode_udp_reply(&reply.target, &reply.payload);
+            if let Err(err) = session.relay_socket.send_to(&packet, client_addr).await {
+                warn!("Failed to deliver SOCKS5 UDP ASSOCIATE reply to {client_addr}: {err}");
+            }
+        }
+    }
+
+    /// Encodes `payload` from `target` as a SOCKS5 UDP request/reply header
+    /// followed by the payload itself, per RFC 1928 section 7.
+    fn encode_udp_reply(target: &Socks5Target, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x00, 0x00, 0x00];
+
+        match target {
+            Socks5Target::SocketAddr(SocketAddr::V4(addr)) => {
+                packet.push(ATYP_IPV4);
+                packet.extend_from_slice(&addr.ip().octets());
+                packet.extend_from_slice(&addr.port().to_be_bytes());
+            }
+            Socks5Target::SocketAddr(SocketAddr::V6(addr)) => {
+                packet.push(ATYP_IPV6);
+                packet.extend_from_slice(&addr.ip().octets());
+                packet.extend_from_slice(&addr.port().to_be_bytes());
+            }
+            Socks5Target::Domain(domain, port) => {
+                packet.push(ATYP_DOMAIN);
+                packet.push(domain.len() as u8);
+                packet.extend_from_slice(domain.as_bytes());
+                packet.extend_from_slice(&port.to_be_bytes());
+            }
+        }
+
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn parse_udp_request(packet: &[u8]) -> Result<(Socks5Target, Vec<u8>)> {
+        if packet.len() < 4 {
+            bail!("SOCKS5 UDP request too short");
+        }
+
+        let fragment = packet[2];
+        if fragment != 0 {
+            bail!("Fragmented SOCKS5 UDP requests are not supported");
+        }
+
+        let address_type = packet[3];
+        let mut offset = 4;
+
+        let target = match address_type {
+            ATYP_IPV4 => {
+                let octets: [u8; 4] = packet[offset..offset + 4].try_into()?;
+                offset += 4;
+                let port = u16::from_be_bytes(packet[offset..offset + 2].try_into()?);
+                offset += 2;
+
+                Socks5Target::SocketAddr(SocketAddr::new(IpAddr::V4(Ipv4Addr::from(octets)), port))
+            }
+            ATYP_IPV6 => {
+                let octets: [u8; 16] = packet[offset..offset + 16].try_into()?;
+                offset += 16;
+                let port = u16::from_be_bytes(packet[offset..offset + 2].try_into()?);
+                offset += 2;
+
+                Socks5Target::SocketAddr(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port))
+            }
+            ATYP_DOMAIN => {
+                let len = packet[offset] as usize;
+                offset += 1;
+                let domain = String::from_utf8(packet[offset..offset + len].to_vec())?;
+                offset += len;
+                let port = u16::from_be_bytes(packet[offset..offset + 2].try_into()?);
+                offset += 2;
+
+                Socks5Target::Domain(domain, port)
+            }
+            _ => bail!("Unsupported SOCKS5 UDP address type: {address_type}"),
+        };
+
+        Ok((target, packet[offset..].to_vec()))
+    }
+
+    async fn write_reply(socket: &mut TcpStream, reply: u8) -> Result<()> {
+        socket
+            .write_all(&[SOCKS5_VERSION, reply, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+            .await?;
+
+        Ok(())
+    }
+
+    async fn write_udp_associate_reply(
+        socket: &mut TcpStream,
+        bound_addr: SocketAddr,
+    ) -> Result<()> {
+        let mut reply = vec![SOCKS5_VERSION, REPLY_SUCCESS, 0x00];
+
+        match bound_addr {
+            SocketAddr::V4(addr) => {
+                reply.push(ATYP_IPV4);
+                reply.extend_from_slice(&addr.ip().octets());
+            }
+            SocketAddr::V6(addr) => {
+                reply.push(ATYP_IPV6);
+                reply.extend_from_slice(&addr.ip().octets());
+            }
+        }
+
+        reply.extend_from_slice(&bound_addr.port().to_be_bytes());
+        socket.write_all(&reply).await?;
+
+        Ok(())
+    }
+}
+
+/// Runs the server side of the SOCKS5 ingress mode for a single connection:
+/// accepts each bidirectional stream and UDP ASSOCIATE datagram a peer's
+/// `Socks5Server` sends, dials the target embedded in it, and relays the
+/// response back over the same stream or as a tagged reply datagram.
+pub async fn serve_socks5_targets(connection: Arc<Connection>, buffer_size: usize) -> Result<()> {
+    let datagrams = tokio::spawn(accept_udp_datagrams(connection.clone()));
+
+    let result = accept_streams(connection, buffer_size).await;
+
+    datagrams.abort();
+    result
+}
+
+/// Accepts each bidirectional stream opened by `Socks5Server::handle_connect`,
+/// reads the `Socks5Target` header it wrote, dials that target, and relays
+/// bytes between it and the stream until either side closes.
+async fn accept_streams(connection: Arc<Connection>, buffer_size: usize) -> Result<()> {
+    loop {
+        let (send, recv) = connection.accept_bi().await?;
+
+        tokio::spawn(async move {
+            if let Err(err) = serve_connect_stream(send, recv, buffer_size).await {
+                warn!("SOCKS5 forwarded stream ended: {err}");
+            }
+        });
+    }
+}
+
+async fn serve_connect_stream(
+    mut send: SendStream,
+    mut recv: RecvStream,
+    buffer_size: usize,
+) -> Result<()> {
+    let header_len = recv.read_u32().await? as usize;
+    let mut header = vec![0u8; header_len];
+    recv.read_exact(&mut header).await?;
+
+    let (target, _): (Socks5Target, usize) =
+        bincode::decode_from_slice(&header, bincode::config::standard())?;
+
+    let socket = match dial_target(&target).await {
+        Ok(socket) => {
+            send.write_u8(DIAL_ACK_SUCCESS).await?;
+            socket
+        }
+        Err(err) => {
+            send.write_u8(DIAL_ACK_FAILURE).await?;
+            return Err(err);
+        }
+    };
+    let (mut socket_read, mut socket_write) = socket.into_split();
+
+    let upload = async {
+        let mut buf = vec![0u8; buffer_size];
+        loop {
+            match recv.read(&mut buf).await? {
+                Some(0) | None => break,
+                Some(read) => socket_write.write_all(&buf[..read]).await?,
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    };
+
+    let download = async {
+        let mut buf = vec![0u8; buffer_size];
+        loop {
+            let read = socket_read.read(&mut buf).await?;
+            if read == 0 {
+                break;
+            }
+            send.write_all(&buf[..read]).await?;
+        }
+        send.finish()
+            .map_err(|err| anyhow!("Failed to close SOCKS5 forwarded stream: {err}"))
+    };
+
+    tokio::try_join!(upload, download)?;
+
+    Ok(())
+}
+
+/// Reads datagrams tagged `SOCKS5_UDP_DATAGRAM_TAG` off `connection` until an
+/// error occurs, dialing each one's target and sending the reply back as a
+/// datagram tagged the same way and carrying the same `session_id`.
+async fn accept_udp_datagrams(connection: Arc<Connection>) -> Result<()> {
+    loop {
+        let datagram = connection.read_datagram().await?;
+        if datagram.first() != Some(&SOCKS5_UDP_DATAGRAM_TAG) {
+            continue;
+        }
+
+        let (incoming, _): (Socks5UdpDatagram, usize) =
+            match bincode::decode_from_slice(&datagram[1..], bincode::config::standard()) {
+                Ok(decoded) => decoded,
+                Err(err) => {
+                    warn!("Failed to decode SOCKS5 UDP ASSOCIATE request datagram: {err}");
+                    continue;
+                }
+            };
+
+        let connection = connection.clone();
+        tokio::spawn(async move {
+            if let Err(err) = relay_udp_datagram(&connection, incoming).await {
+                warn!("SOCKS5 UDP ASSOCIATE datagram dropped: {err}");
+            }
+        });
+    }
+}
+
+async fn relay_udp_datagram(connection: &Connection, datagram: Socks5UdpDatagram) -> Result<()> {
+    let target_addr = resolve_target(&datagram.target).await?;
+
+    let bind_addr: SocketAddr = match target_addr {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.send_to(&datagram.payload, target_addr).await?;
+
+    let mut buf = vec![0u8; 4096];
+    let read = socket.recv(&mut buf).await?;
+
+    let reply = Socks5UdpDatagram {
+        session_id: datagram.session_id,
+        target: datagram.target,
+        payload: buf[..read].to_vec(),
+    };
+    let mut encoded = vec![SOCKS5_UDP_DATAGRAM_TAG];
+    encoded.extend(bincode::encode_to_vec(&reply, bincode::config::standard())?);
+
+    connection.send_datagram(encoded.into())?;
+
+    Ok(())
+}
+
+/// Resolves a `Socks5Target` to a concrete `SocketAddr`, performing a DNS
+/// lookup for domain targets.
+async fn resolve_target(target: &Socks5Target) -> Result<SocketAddr> {
+    match target {
+        Socks5Target::SocketAddr(addr) => Ok(*addr),
+        Socks5Target::Domain(domain, port) => tokio::net::lookup_host((domain.as_str(), *port))
+            .await?
+            .next()
+            .ok_or_else(|| anyhow!("Failed to resolve '{domain}'")),
+    }
+}
+
+/// Resolves and dials `target`, so the caller can tell the requesting client
+/// whether the connection actually succeeded before relaying traffic.
+async fn dial_target(target: &Socks5Target) -> Result<TcpStream> {
+    let target_addr = resolve_target(target).await?;
+
+    Ok(TcpStream::connect(target_addr).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_udp_request_ipv4_round_trips_through_encode_udp_reply() {
+        let target_addr: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let mut packet = vec![0x00, 0x00, 0x00, ATYP_IPV4];
+        packet.extend_from_slice(&[127, 0, 0, 1]);
+        packet.extend_from_slice(&9000u16.to_be_bytes());
+        packet.extend_from_slice(b"hello");
+
+        let (target, payload) = Socks5Server::parse_udp_request(&packet).unwrap();
+        assert!(matches!(target, Socks5Target::SocketAddr(addr) if addr == target_addr));
+        assert_eq!(payload, b"hello");
+
+        let reply = Socks5Server::encode_udp_reply(&target, &payload);
+        let (reparsed_target, reparsed_payload) = Socks5Server::parse_udp_request(&reply).unwrap();
+        assert!(matches!(reparsed_target, Socks5Target::SocketAddr(addr) if addr == target_addr));
+        assert_eq!(reparsed_payload, b"hello");
+    }
+
+    #[test]
+    fn test_parse_udp_request_domain_round_trips_through_encode_udp_reply() {
+        let mut packet = vec![0x00, 0x00, 0x00, ATYP_DOMAIN, 11];
+        packet.extend_from_slice(b"example.com");
+        packet.extend_from_slice(&443u16.to_be_bytes());
+        packet.extend_from_slice(b"payload");
+
+        let (target, payload) = Socks5Server::parse_udp_request(&packet).unwrap();
+        assert!(
+            matches!(&target, Socks5Target::Domain(domain, port) if domain == "example.com" && *port == 443)
+        );
+        assert_eq!(payload, b"payload");
+
+        let reply = Socks5Server::encode_udp_reply(&target, &payload);
+        let (reparsed_target, reparsed_payload) = Socks5Server::parse_udp_request(&reply).unwrap();
+        assert!(
+            matches!(&reparsed_target, Socks5Target::Domain(domain, port) if domain == "example.com" && *port == 443)
+        );
+        assert_eq!(reparsed_payload, b"payload");
+    }
+
+    #[test]
+    fn test_parse_udp_request_rejects_fragmented_requests() {
+        let packet = vec![0x00, 0x00, 0x01, ATYP_IPV4, 127, 0, 0, 1, 0, 80];
+        assert!(Socks5Server::parse_udp_request(&packet).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_target_returns_socket_addr_directly() {
+        let addr: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+        let resolved = resolve_target(&Socks5Target::SocketAddr(addr))
+            .await
+            .unwrap();
+        assert_eq!(resolved, addr);
+    }
+}
@@ -1,6 +1,8 @@
 use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
 use bytes::BytesMut;
@@ -10,18 +12,149 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
+use tokio::time::sleep;
 use tokio_tun::{Tun, TunBuilder};
 use tracing::warn;
 
+use crate::auth::{Auth, SessionToken};
+use crate::forward::ForwardWorker;
+
+/// Size in bytes of the header prepended to every fragment: a `u16` packet
+/// id followed by a `u16` fragment index and a `u16` fragment count. Widened
+/// from single bytes so a packet needing more than 255 fragments is
+/// expressed correctly instead of silently truncated.
+const FRAGMENT_HEADER_SIZE: usize = 6;
+
+/// How long an incomplete set of fragments is kept around before being
+/// discarded by the reassembly table's reaper.
+const FRAGMENT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Leading byte on every datagram `TunWorker` sends or receives, identifying
+/// whether it carries a whole IP packet or a single fragment of one, so the
+/// receive path knows whether to hand it straight to the TUN device or feed
+/// it through the `FragmentReassembler` first.
+const DATAGRAM_KIND_WHOLE: u8 = 0;
+const DATAGRAM_KIND_FRAGMENT: u8 = 1;
+
+/// A partially-received fragmented packet, keyed by packet id in
+/// `FragmentReassembler`.
+struct PartialPacket {
+    fragments: Vec<Option<BytesMut>>,
+    received: usize,
+    last_seen: Instant,
+}
+
+/// Reassembles IP packets that were fragmented because they exceeded the
+/// negotiated QUIC datagram size. Incomplete sets of fragments are evicted
+/// after `FRAGMENT_REASSEMBLY_TIMEOUT` so a dropped fragment cannot leak
+/// memory indefinitely.
+#[derive(Default)]
+pub struct FragmentReassembler {
+    partial_packets: RwLock<HashMap<u16, PartialPacket>>,
+}
+
+impl FragmentReassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds a received datagram into the reassembler. Returns the
+    /// reconstructed packet once every fragment of its packet id has
+    /// arrived, or `None` while the set is still incomplete.
+    ///
+    /// Returns an `Err` instead of panicking if the fragment's index or
+    /// count is inconsistent, e.g. a corrupted or adversarial datagram -
+    /// the caller should drop such a datagram rather than propagate the error.
+    ///
+    /// ### Arguments
+    /// - `datagram` - a single fragment, header included, as produced by `TunWorker::fragment_packet` but with its leading datagram kind tag already stripped
+    pub async fn insert(&self, datagram: BytesMut) -> Result<Option<BytesMut>> {
+        if datagram.len() < FRAGMENT_HEADER_SIZE {
+            return Err(anyhow!(
+                "Received a fragment shorter than the fragment header"
+            ));
+        }
+
+        let packet_id = u16::from_be_bytes([datagram[0], datagram[1]]);
+        let fragment_index = u16::from_be_bytes([datagram[2], datagram[3]]) as usize;
+        let fragment_count = u16::from_be_bytes([datagram[4], datagram[5]]) as usize;
+        let payload = BytesMut::from(&datagram[FRAGMENT_HEADER_SIZE..]);
+
+        if fragment_count == 0 || fragment_index >= fragment_count {
+            return Err(anyhow!(
+                "Received a fragment with an out-of-range index {fragment_index} (count {fragment_count})"
+            ));
+        }
+
+        let mut partial_packets = self.partial_packets.write().await;
+
+        // A reused packet id with a different fragment count means the
+        // previous, never-completed set belonged to an earlier packet -
+        // discard it instead of mixing its fragments with the new set.
+        if let Some(existing) = partial_packets.get(&packet_id) {
+            if existing.fragments.len() != fragment_count {
+                partial_packets.remove(&packet_id);
+            }
+        }
+
+        let entry = partial_packets
+            .entry(packet_id)
+            .or_insert_with(|| PartialPacket {
+                fragments: vec![None; fragment_count],
+                received: 0,
+                last_seen: Instant::now(),
+            });
+
+        entry.last_seen = Instant::now();
+
+        if entry.fragments[fragment_index].is_none() {
+            entry.fragments[fragment_index] = Some(payload);
+            entry.received += 1;
+        }
+
+        if entry.received < fragment_count {
+            return Ok(None);
+        }
+
+        let entry = partial_packets
+            .remove(&packet_id)
+            .expect("entry was just looked up above");
+
+        let mut packet = BytesMut::new();
+        for fragment in entry.fragments {
+            packet.extend_from_slice(&fragment.expect("all fragments present"));
+        }
+
+        Ok(Some(packet))
+    }
+
+    /// Periodically discards fragment sets that have not completed within
+    /// `FRAGMENT_REASSEMBLY_TIMEOUT`. Intended to be spawned as a background task.
+    pub async fn run_reaper(&self) {
+        loop {
+            sleep(FRAGMENT_REASSEMBLY_TIMEOUT).await;
+
+            self.partial_packets
+                .write()
+                .await
+                .retain(|_, partial| partial.last_seen.elapsed() < FRAGMENT_REASSEMBLY_TIMEOUT);
+        }
+    }
+}
+
 pub struct TunWorker {
     tun_read: Arc<RwLock<ReadHalf<Tun>>>,
     tun_write: Arc<RwLock<WriteHalf<Tun>>>,
     write_queue_sender: Arc<UnboundedSender<BytesMut>>,
     write_queue_receiver: Arc<RwLock<UnboundedReceiver<BytesMut>>>,
     active_connections: Arc<RwLock<HashMap<IpAddr, Arc<Connection>>>>,
+    forward_workers: Arc<RwLock<HashMap<IpAddr, Arc<ForwardWorker>>>>,
+    fragment_reassembler: Arc<FragmentReassembler>,
     buffer_size: usize,
+    next_packet_id: Arc<AtomicU16>,
     reader_task: Option<JoinHandle<Result<()>>>,
     writer_task: Option<JoinHandle<Result<()>>>,
+    reaper_task: Option<JoinHandle<()>>,
 }
 
 impl TunWorker {
@@ -35,17 +168,122 @@ impl TunWorker {
             write_queue_sender: Arc::new(write_queue_sender),
             write_queue_receiver: Arc::new(RwLock::new(write_queue_receiver)),
             active_connections: Arc::new(RwLock::new(HashMap::new())),
+            forward_workers: Arc::new(RwLock::new(HashMap::new())),
+            fragment_reassembler: Arc::new(FragmentReassembler::new()),
             buffer_size,
+            next_packet_id: Arc::new(AtomicU16::new(0)),
             reader_task: None,
             writer_task: None,
+            reaper_task: None,
         }
     }
 
-    pub async fn add_connection(&self, remote_addr: IpAddr, connection: Arc<Connection>) {
+    /// Registers the connection to use for a given tunnel IP, and starts a
+    /// `ForwardWorker` to accept any port forwards multiplexed over it
+    /// alongside the full-tunnel datagram path. If a client reconnects (e.g.
+    /// after presenting a resumed `SessionToken`) and is re-bound to the same
+    /// address, this re-registers the entry in place of the old one rather
+    /// than requiring a separate removal step.
+    ///
+    /// ### Returns
+    /// - the previous connection registered for `remote_addr`, if any, so the caller can close out a stale one left over from a dropped session
+    pub async fn add_connection(
+        &self,
+        remote_addr: IpAddr,
+        connection: Arc<Connection>,
+    ) -> Option<Arc<Connection>> {
+        let forward_worker = Arc::new(ForwardWorker::new(connection.clone(), self.buffer_size));
+        if let Err(err) = forward_worker.start_accepting() {
+            warn!("Failed to start accepting forwards for {remote_addr}: {err}");
+        }
+        self.forward_workers
+            .write()
+            .await
+            .insert(remote_addr, forward_worker);
+
+        tokio::spawn(Self::process_incoming_datagrams(
+            connection.clone(),
+            self.write_queue_sender.clone(),
+            self.fragment_reassembler.clone(),
+        ));
+
         self.active_connections
             .write()
             .await
-            .insert(remote_addr, connection);
+            .insert(remote_addr, connection)
+    }
+
+    /// Reads datagrams arriving on `connection`, reassembling fragments via
+    /// `fragment_reassembler` before handing the resulting IP packet off to
+    /// the TUN device's write queue. Runs until the connection closes.
+    async fn process_incoming_datagrams(
+        connection: Arc<Connection>,
+        write_queue_sender: Arc<UnboundedSender<BytesMut>>,
+        fragment_reassembler: Arc<FragmentReassembler>,
+    ) -> Result<()> {
+        loop {
+            let datagram = connection.read_datagram().await?;
+
+            match Self::handle_incoming_datagram(datagram, &fragment_reassembler).await {
+                Ok(Some(packet)) => {
+                    let _ = write_queue_sender.send(packet);
+                }
+                Ok(None) => continue,
+                Err(err) => {
+                    warn!(
+                        "Dropping a datagram from {}: {err}",
+                        connection.remote_address()
+                    );
+                }
+            }
+        }
+    }
+
+    /// Strips the datagram kind tag and either returns a whole IP packet
+    /// directly, or feeds a fragment into `fragment_reassembler`, returning
+    /// the reconstructed packet once complete.
+    async fn handle_incoming_datagram(
+        datagram: bytes::Bytes,
+        fragment_reassembler: &FragmentReassembler,
+    ) -> Result<Option<BytesMut>> {
+        if datagram.is_empty() {
+            return Err(anyhow!("Received an empty datagram"));
+        }
+
+        let kind = datagram[0];
+        let payload = BytesMut::from(&datagram[1..]);
+
+        match kind {
+            DATAGRAM_KIND_WHOLE => Ok(Some(payload)),
+            DATAGRAM_KIND_FRAGMENT => fragment_reassembler.insert(payload).await,
+            other => Err(anyhow!("Received a datagram with unknown kind tag {other}")),
+        }
+    }
+
+    /// Returns the `ForwardWorker` handling forwards for `remote_addr`'s
+    /// connection, if one is currently registered, so callers can
+    /// `request_forward`/`request_close` on it.
+    pub async fn forward_worker(&self, remote_addr: IpAddr) -> Option<Arc<ForwardWorker>> {
+        self.forward_workers.read().await.get(&remote_addr).cloned()
+    }
+
+    /// Resumes a client's session after it reconnects and presents
+    /// `AuthClientMessage::SessionToken`, re-binding `connection` to the
+    /// tunnel address the session was previously assigned instead of
+    /// requiring a fresh one from the address pool.
+    ///
+    /// ### Returns
+    /// - the stale connection previously registered for the resumed address, if any, so the caller can close it out
+    pub async fn resume_connection(
+        &self,
+        auth: &Auth,
+        username: &str,
+        session_token: SessionToken,
+        connection: Arc<Connection>,
+    ) -> Result<Option<Arc<Connection>>> {
+        let addr = auth.resume_session_addr(username, session_token)?;
+
+        Ok(self.add_connection(addr, connection).await)
     }
 
     pub fn get_tun_sender(&self) -> Arc<UnboundedSender<BytesMut>> {
@@ -62,17 +300,24 @@ impl TunWorker {
         let write_queue_receiver = self.write_queue_receiver.clone();
         let active_connections = self.active_connections.clone();
         let buffer_size = self.buffer_size;
+        let next_packet_id = self.next_packet_id.clone();
 
         self.reader_task = Some(tokio::spawn(Self::process_incoming_data(
             tun_read,
             active_connections,
             buffer_size,
+            next_packet_id,
         )));
         self.writer_task = Some(tokio::spawn(Self::process_outgoing_data(
             tun_write,
             write_queue_receiver,
         )));
 
+        let fragment_reassembler = self.fragment_reassembler.clone();
+        self.reaper_task = Some(tokio::spawn(async move {
+            fragment_reassembler.run_reaper().await
+        }));
+
         Ok(())
     }
 
@@ -93,6 +338,11 @@ impl TunWorker {
 
         self.writer_task = None;
 
+        if let Some(reaper_task) = self.reaper_task.as_mut() {
+            reaper_task.abort();
+        }
+        self.reaper_task = None;
+
         Ok(())
     }
 
@@ -100,6 +350,7 @@ impl TunWorker {
         tun_read: Arc<RwLock<ReadHalf<Tun>>>,
         active_connections: Arc<RwLock<HashMap<IpAddr, Arc<Connection>>>>,
         buffer_size: usize,
+        next_packet_id: Arc<AtomicU16>,
     ) -> Result<()> {
         let mut tun_read = tun_read.write().await;
 
@@ -132,17 +383,66 @@ impl TunWorker {
                 )
             })?;
 
-            if buf.len() > max_datagram_size {
-                warn!(
-                    "Dropping packet of size {} due to maximum datagram size being {}",
-                    buf.len(),
-                    max_datagram_size
-                );
+            // Reserve a byte for the datagram kind tag on top of whatever
+            // fragmentation may add.
+            if buf.len() + 1 <= max_datagram_size {
+                let mut datagram = BytesMut::with_capacity(buf.len() + 1);
+                datagram.extend_from_slice(&[DATAGRAM_KIND_WHOLE]);
+                datagram.extend_from_slice(&buf);
+
+                connection.send_datagram(datagram.into())?;
                 continue;
             }
 
-            connection.send_datagram(buf.into())?;
+            let packet_id = next_packet_id.fetch_add(1, Ordering::Relaxed);
+
+            for fragment in Self::fragment_packet(&buf, max_datagram_size, packet_id)? {
+                connection.send_datagram(fragment.into())?;
+            }
+        }
+    }
+
+    /// Splits `packet`, which is larger than `max_datagram_size`, into
+    /// fragments that each fit within a single datagram, prefixing each one
+    /// with the datagram kind tag followed by a `(packet_id, fragment_index,
+    /// fragment_count)` header so the receiving side's `FragmentReassembler`
+    /// can put it back together.
+    ///
+    /// Returns an `Err` instead of silently truncating if `packet` would
+    /// require more than `u16::MAX` fragments.
+    fn fragment_packet(
+        packet: &[u8],
+        max_datagram_size: usize,
+        packet_id: u16,
+    ) -> Result<Vec<BytesMut>> {
+        let chunk_size = max_datagram_size - 1 - FRAGMENT_HEADER_SIZE;
+        let fragment_count = packet.len().div_ceil(chunk_size);
+
+        if fragment_count > u16::MAX as usize {
+            return Err(anyhow!(
+                "Packet requires {fragment_count} fragments, which exceeds the maximum of {}",
+                u16::MAX
+            ));
         }
+
+        let fragment_count = fragment_count as u16;
+
+        Ok(packet
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(fragment_index, chunk)| {
+                let fragment_index = fragment_index as u16;
+                let mut fragment = BytesMut::with_capacity(1 + FRAGMENT_HEADER_SIZE + chunk.len());
+
+                fragment.extend_from_slice(&[DATAGRAM_KIND_FRAGMENT]);
+                fragment.extend_from_slice(&packet_id.to_be_bytes());
+                fragment.extend_from_slice(&fragment_index.to_be_bytes());
+                fragment.extend_from_slice(&fragment_count.to_be_bytes());
+                fragment.extend_from_slice(chunk);
+
+                fragment
+            })
+            .collect())
     }
 
     async fn process_outgoing_data(
@@ -165,6 +465,61 @@ impl TunWorker {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::{FragmentReassembler, TunWorker};
+    use bytes::BytesMut;
+
+    #[tokio::test]
+    async fn test_reassembles_out_of_order_fragments() {
+        let packet = b"hello from an oversized tunnel packet".to_vec();
+        // Small enough that `chunks` below yields multiple fragments.
+        let max_datagram_size = 12;
+
+        let fragments = TunWorker::fragment_packet(&packet, max_datagram_size, 42).unwrap();
+        assert!(fragments.len() > 1);
+
+        let reassembler = FragmentReassembler::new();
+        let mut reordered: Vec<BytesMut> = fragments
+            .into_iter()
+            // Strip the datagram kind tag, as `handle_incoming_datagram` would.
+            .map(|fragment| BytesMut::from(&fragment[1..]))
+            .collect();
+        reordered.reverse();
+
+        let mut reassembled = None;
+        for fragment in reordered {
+            reassembled = reassembler.insert(fragment).await.unwrap();
+        }
+
+        assert_eq!(reassembled.unwrap(), BytesMut::from(&packet[..]));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_out_of_range_fragment_index_instead_of_panicking() {
+        let reassembler = FragmentReassembler::new();
+
+        let mut fragment = BytesMut::new();
+        fragment.extend_from_slice(&1u16.to_be_bytes()); // packet_id
+        fragment.extend_from_slice(&5u16.to_be_bytes()); // fragment_index (out of range)
+        fragment.extend_from_slice(&2u16.to_be_bytes()); // fragment_count
+        fragment.extend_from_slice(b"payload");
+
+        assert!(reassembler.insert(fragment).await.is_err());
+    }
+
+    #[test]
+    fn test_fragment_packet_rejects_more_than_u16_max_fragments() {
+        // One byte of payload per fragment at this datagram size, so a
+        // packet one byte larger than `u16::MAX` fragments can hold needs
+        // more fragments than a `u16` index can represent.
+        let max_datagram_size = 1 + super::FRAGMENT_HEADER_SIZE + 1;
+        let packet = vec![0u8; u16::MAX as usize + 1];
+
+        assert!(TunWorker::fragment_packet(&packet, max_datagram_size, 0).is_err());
+    }
+}
+
 pub fn make_tun(
     name: String,
     local_addr: Ipv4Addr,